@@ -1,6 +1,8 @@
+use std::borrow::Cow;
 use std::fmt;
 use std::hash;
 use std::ops;
+use std::str::CowString;
 
 /// A trait that encapsulates a `Vec<T>` or a `&[T]`.
 pub trait IntoVector<T> {
@@ -69,6 +71,35 @@ impl ByteString {
         String::from_utf8(self.into_bytes()).map_err(ByteString)
     }
 
+    /// Consumes this byte string, decoding it as UTF-8 and replacing
+    /// each maximal invalid subsequence with a single U+FFFD replacement
+    /// character, rather than failing outright as `into_utf8_string`
+    /// does.
+    ///
+    /// This splits invalid input into the exact same maximal invalid
+    /// subsequences as `chars`, `char_indices` and the `Show` impl below
+    /// (all of them use `decode_utf8_char`; `Show` writes each such run as
+    /// hex escapes rather than U+FFFD), so `bs.into_utf8_string_lossy()` and
+    /// `bs.chars().collect::<String>()` always agree on how many
+    /// replacement characters a given byte string produces.
+    pub fn into_utf8_string_lossy(self) -> String {
+        match String::from_utf8(self.into_bytes()) {
+            Ok(s) => s,
+            Err(bytes) => utf8_lossy_string(bytes.as_slice()),
+        }
+    }
+
+    /// Returns this byte string decoded as UTF-8, replacing each maximal
+    /// invalid subsequence with a single U+FFFD replacement character
+    /// (see `into_utf8_string_lossy`). Borrows rather than allocates
+    /// when the bytes are already valid UTF-8.
+    pub fn to_utf8_lossy<'a>(&'a self) -> CowString<'a> {
+        match ::std::str::from_utf8(self.as_bytes()) {
+            Ok(s) => Cow::Borrowed(s),
+            Err(_) => Cow::Owned(utf8_lossy_string(self.as_bytes())),
+        }
+    }
+
     /// Return the number of bytes in the string.
     pub fn len(&self) -> uint {
         self.as_bytes().len()
@@ -78,24 +109,611 @@ impl ByteString {
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
+
+    /// Returns the byte offset of the first occurrence of `needle`, or
+    /// `None` if it does not occur anywhere in this byte string.
+    pub fn find(&self, needle: &[u8]) -> Option<uint> {
+        find_bytes(self.as_bytes(), needle)
+    }
+
+    /// Returns the byte offset of the last occurrence of `needle`, or
+    /// `None` if it does not occur anywhere in this byte string.
+    pub fn rfind(&self, needle: &[u8]) -> Option<uint> {
+        rfind_bytes(self.as_bytes(), needle)
+    }
+
+    /// Returns `true` if and only if this byte string contains `needle`
+    /// somewhere within it.
+    pub fn contains(&self, needle: &[u8]) -> bool {
+        self.find(needle).is_some()
+    }
+
+    /// Returns an iterator over the pieces of this byte string, split on
+    /// occurrences of `sep`. If `sep` occurs at the very end of this byte
+    /// string, then an empty piece is yielded last, matching the way CSV
+    /// cells are typically split.
+    pub fn split_str<'a>(&'a self, sep: &'a [u8]) -> SplitStr<'a> {
+        SplitStr { haystack: Some(self.as_bytes()), sep: sep }
+    }
+
+    /// Returns a new byte string with leading and trailing ASCII
+    /// whitespace removed.
+    ///
+    /// Only ASCII whitespace (space, tab, `\n`, `\r` and form feed) is
+    /// trimmed. Non-ASCII bytes are left untouched, since whether they
+    /// represent whitespace is ambiguous without first knowing (and
+    /// applying) their encoding.
+    pub fn trim(&self) -> ByteString {
+        let bytes = self.as_bytes();
+        let mut start = 0u;
+        while start < bytes.len() && is_ascii_whitespace(bytes[start]) {
+            start += 1;
+        }
+        let mut end = bytes.len();
+        while end > start && is_ascii_whitespace(bytes[end - 1]) {
+            end -= 1;
+        }
+        ByteString::from_bytes(bytes.slice(start, end))
+    }
+
+    /// Like `trim`, but only removes leading ASCII whitespace.
+    pub fn trim_start(&self) -> ByteString {
+        let bytes = self.as_bytes();
+        let mut start = 0u;
+        while start < bytes.len() && is_ascii_whitespace(bytes[start]) {
+            start += 1;
+        }
+        ByteString::from_bytes(bytes.slice_from(start))
+    }
+
+    /// Like `trim`, but only removes trailing ASCII whitespace.
+    pub fn trim_end(&self) -> ByteString {
+        let bytes = self.as_bytes();
+        let mut end = bytes.len();
+        while end > 0 && is_ascii_whitespace(bytes[end - 1]) {
+            end -= 1;
+        }
+        ByteString::from_bytes(bytes.slice_to(end))
+    }
+
+    /// Replaces all non-overlapping occurrences of `from` with `to`,
+    /// returning a new byte string.
+    pub fn replace(&self, from: &[u8], to: &[u8]) -> ByteString {
+        if from.is_empty() {
+            return ByteString::from_bytes(self.as_bytes());
+        }
+        let mut result: Vec<u8> = Vec::with_capacity(self.len());
+        let mut rest = self.as_bytes();
+        loop {
+            match find_bytes(rest, from) {
+                Some(pos) => {
+                    result.push_all(rest.slice_to(pos));
+                    result.push_all(to);
+                    rest = rest.slice_from(pos + from.len());
+                }
+                None => {
+                    result.push_all(rest);
+                    break;
+                }
+            }
+        }
+        ByteString::from_bytes(result)
+    }
+
+    /// Returns a copy of this byte string where each ASCII uppercase
+    /// byte has been replaced with its ASCII lowercase equivalent. Bytes
+    /// outside the ASCII range are left untouched.
+    pub fn to_ascii_lowercase(&self) -> ByteString {
+        ByteString::from_bytes(self.as_bytes().iter().map(|&b| {
+            if b >= b'A' && b <= b'Z' { b + 32 } else { b }
+        }).collect::<Vec<u8>>())
+    }
+
+    /// Returns a copy of this byte string where each ASCII lowercase
+    /// byte has been replaced with its ASCII uppercase equivalent. Bytes
+    /// outside the ASCII range are left untouched.
+    pub fn to_ascii_uppercase(&self) -> ByteString {
+        ByteString::from_bytes(self.as_bytes().iter().map(|&b| {
+            if b >= b'a' && b <= b'z' { b - 32 } else { b }
+        }).collect::<Vec<u8>>())
+    }
+
+    /// Decodes this byte string using `enc`, handling unmappable or
+    /// ill-formed byte sequences according to `trap`. Unlike
+    /// `into_utf8_string`, this allows reading CSV fields that are encoded
+    /// in a legacy encoding such as Windows-1252 rather than UTF-8.
+    pub fn decode_with<E: ?Sized + Encoding>(&self, enc: &E, trap: Trap) -> Result<String, DecodeError> {
+        enc.decode(self.as_bytes(), trap)
+    }
+
+    /// Returns an iterator over the `char`s of this byte string, decoded
+    /// lazily as UTF-8. Each maximal invalid subsequence yields a single
+    /// U+FFFD replacement character, so this never fails even when the
+    /// underlying bytes aren't valid UTF-8 -- unlike `into_utf8_string`.
+    pub fn chars<'a>(&'a self) -> Chars<'a> {
+        Chars { rest: self.as_bytes() }
+    }
+
+    /// Like `chars`, but also yields the byte range each `char` was
+    /// decoded from. For an invalid subsequence, the range covers the
+    /// whole maximal run of invalid bytes that was replaced by the
+    /// single U+FFFD yielded alongside it.
+    pub fn char_indices<'a>(&'a self) -> CharIndices<'a> {
+        CharIndices { rest: self.as_bytes(), pos: 0 }
+    }
+
+    /// Returns an iterator over the extended grapheme clusters of this
+    /// byte string, decoded lazily from (possibly invalid) UTF-8.
+    ///
+    /// This only implements the common cases of grapheme segmentation --
+    /// a CRLF pair, and a base character followed by combining marks --
+    /// rather than the full UAX #29 break property tables. That's enough
+    /// to safely count display columns or truncate fields without
+    /// splitting an accented character in two, which is what this is
+    /// mostly used for; a dedicated Unicode segmentation crate is a
+    /// better fit for applications that need full conformance.
+    pub fn graphemes<'a>(&'a self) -> Graphemes<'a> {
+        Graphemes { chars: self.chars(), lookahead: None }
+    }
+}
+
+/// An iterator over the `char`s of a `ByteString`. See `ByteString::chars`.
+pub struct Chars<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Iterator<char> for Chars<'a> {
+    fn next(&mut self) -> Option<char> {
+        if self.rest.is_empty() {
+            return None;
+        }
+        match decode_utf8_char(self.rest) {
+            (Some(c), len) => {
+                self.rest = self.rest.slice_from(len);
+                Some(c)
+            }
+            (None, len) => {
+                let len = if len == 0 { 1 } else { len };
+                self.rest = self.rest.slice_from(len);
+                Some('\u{FFFD}')
+            }
+        }
+    }
+}
+
+/// An iterator over the `char`s of a `ByteString` along with the byte
+/// range each one was decoded from. See `ByteString::char_indices`.
+pub struct CharIndices<'a> {
+    rest: &'a [u8],
+    pos: uint,
+}
+
+impl<'a> Iterator<(uint, uint, char)> for CharIndices<'a> {
+    fn next(&mut self) -> Option<(uint, uint, char)> {
+        if self.rest.is_empty() {
+            return None;
+        }
+        let start = self.pos;
+        match decode_utf8_char(self.rest) {
+            (Some(c), len) => {
+                self.rest = self.rest.slice_from(len);
+                self.pos += len;
+                Some((start, self.pos, c))
+            }
+            (None, len) => {
+                let len = if len == 0 { 1 } else { len };
+                self.rest = self.rest.slice_from(len);
+                self.pos += len;
+                Some((start, self.pos, '\u{FFFD}'))
+            }
+        }
+    }
+}
+
+/// An iterator over the extended grapheme clusters of a `ByteString`.
+/// See `ByteString::graphemes`.
+pub struct Graphemes<'a> {
+    chars: Chars<'a>,
+    lookahead: Option<char>,
+}
+
+impl<'a> Iterator<String> for Graphemes<'a> {
+    fn next(&mut self) -> Option<String> {
+        let first = match self.lookahead.take() {
+            Some(c) => c,
+            None => match self.chars.next() {
+                Some(c) => c,
+                None => return None,
+            },
+        };
+        let mut cluster = String::new();
+        cluster.push(first);
+        if first == '\r' {
+            match self.chars.next() {
+                Some('\n') => cluster.push('\n'),
+                Some(c) => self.lookahead = Some(c),
+                None => {}
+            }
+            return Some(cluster);
+        }
+        loop {
+            match self.chars.next() {
+                Some(c) if is_combining_mark(c) => cluster.push(c),
+                Some(c) => {
+                    self.lookahead = Some(c);
+                    break;
+                }
+                None => break,
+            }
+        }
+        Some(cluster)
+    }
+}
+
+/// Returns `true` if `c` falls in one of the Unicode combining marks
+/// blocks, and should therefore be grouped with the preceding base
+/// character into a single grapheme cluster.
+fn is_combining_mark(c: char) -> bool {
+    let cp = c as u32;
+    (cp >= 0x0300 && cp <= 0x036F) ||
+    (cp >= 0x1AB0 && cp <= 0x1AFF) ||
+    (cp >= 0x1DC0 && cp <= 0x1DFF) ||
+    (cp >= 0x20D0 && cp <= 0x20FF) ||
+    (cp >= 0xFE20 && cp <= 0xFE2F)
+}
+
+/// Returns `true` if `b` is an ASCII whitespace byte (space, tab, `\n`,
+/// `\r` or form feed).
+fn is_ascii_whitespace(b: u8) -> bool {
+    match b {
+        b' ' | b'\t' | b'\n' | b'\r' | 0x0Cu8 => true,
+        _ => false,
+    }
+}
+
+/// Returns the byte offset of the first occurrence of `needle` in
+/// `haystack`, or `None` if it does not occur.
+///
+/// Single-byte needles are found with a direct byte scan. Longer needles
+/// are found with the Two-Way string matching algorithm, which runs in
+/// linear time and constant space by first splitting the needle into a
+/// critical factorization and then skipping ahead by the needle's period
+/// on every mismatch, rather than re-scanning byte by byte as a naive
+/// search would.
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<uint> {
+    if needle.is_empty() {
+        return Some(0);
+    }
+    if needle.len() > haystack.len() {
+        return None;
+    }
+    if needle.len() == 1 {
+        return haystack.iter().position(|&b| b == needle[0]);
+    }
+    two_way_search(haystack, needle)
+}
+
+/// Returns the byte offset of the last occurrence of `needle` in
+/// `haystack`, or `None` if it does not occur.
+fn rfind_bytes(haystack: &[u8], needle: &[u8]) -> Option<uint> {
+    if needle.is_empty() {
+        return Some(haystack.len());
+    }
+    if needle.len() > haystack.len() {
+        return None;
+    }
+    let mut i = haystack.len() - needle.len();
+    loop {
+        if haystack.slice(i, i + needle.len()) == needle {
+            return Some(i);
+        }
+        if i == 0 {
+            return None;
+        }
+        i -= 1;
+    }
+}
+
+/// Computes the maximal suffix of `x`, along with its period, under
+/// either the `<` ordering (`want_larger == false`) or the `>` ordering
+/// (`want_larger == true`). Taking the suffix with the larger starting
+/// position of the two orderings gives the critical factorization point
+/// used by `two_way_search`.
+fn maximal_suffix(x: &[u8], want_larger: bool) -> (uint, uint) {
+    let n = x.len();
+    let mut left = 0u;
+    let mut right = 1u;
+    let mut offset = 0u;
+    let mut period = 1u;
+    while right + offset < n {
+        let a = x[right + offset];
+        let b = x[left + offset];
+        let a_precedes_b = if want_larger { a > b } else { a < b };
+        if a_precedes_b {
+            right += offset + 1;
+            offset = 0;
+            period = right - left;
+        } else if a == b {
+            if offset + 1 == period {
+                right += period;
+                offset = 0;
+            } else {
+                offset += 1;
+            }
+        } else {
+            left = right;
+            right += 1;
+            offset = 0;
+            period = 1;
+        }
+    }
+    (left, period)
+}
+
+/// Splits `needle` at its critical factorization point, returning the
+/// starting offset of the factorization and the needle's period.
+fn critical_factorization(needle: &[u8]) -> (uint, uint) {
+    let (i1, p1) = maximal_suffix(needle, false);
+    let (i2, p2) = maximal_suffix(needle, true);
+    if i1 > i2 { (i1, p1) } else { (i2, p2) }
+}
+
+/// Searches for `pat` in `text` using the Two-Way string matching
+/// algorithm (Crochemore & Perrin, 1991).
+fn two_way_search(text: &[u8], pat: &[u8]) -> Option<uint> {
+    let n = text.len();
+    let m = pat.len();
+    let (ell, period) = critical_factorization(pat);
+
+    // When the needle's prefix up to the critical point recurs with the
+    // needle's period, matching can safely remember how much of the
+    // needle was already verified on the previous attempt and skip
+    // re-checking it. Otherwise, each attempt starts fresh but may skip
+    // ahead by more than the period on a mismatch.
+    let periodic = ell + period <= m && pat.slice_to(ell) == pat.slice(period, period + ell);
+
+    if periodic {
+        let mut j = 0u;
+        let mut memory = 0u;
+        while j <= n - m {
+            // Forward scan of the `v` part (from the critical position
+            // onward), picking up where `memory` says last attempt's
+            // scan already left off verified.
+            let mut i = if ell > memory { ell } else { memory };
+            while i < m && pat[i] == text[j + i] {
+                i += 1;
+            }
+            if i < m {
+                j += i - ell + 1;
+                memory = 0;
+            } else {
+                // Backward scan of the `u` part (everything before the
+                // critical position). A full match requires every byte
+                // down to (and including) index 0 to agree; bytes below
+                // `memory` were already verified, so the scan stops there.
+                let mut i = ell;
+                let mut matched = true;
+                while i > memory {
+                    if pat[i - 1] != text[j + i - 1] {
+                        matched = false;
+                        break;
+                    }
+                    i -= 1;
+                }
+                if matched {
+                    return Some(j);
+                }
+                j += period;
+                memory = m - period;
+            }
+        }
+    } else {
+        let period = {
+            let a = ell + 1;
+            let b = m - ell;
+            if a > b { a } else { b }
+        };
+        let mut j = 0u;
+        while j <= n - m {
+            let mut i = ell;
+            while i < m && pat[i] == text[j + i] {
+                i += 1;
+            }
+            if i < m {
+                j += i - ell + 1;
+            } else {
+                let mut i = ell;
+                let mut matched = true;
+                while i > 0 {
+                    if pat[i - 1] != text[j + i - 1] {
+                        matched = false;
+                        break;
+                    }
+                    i -= 1;
+                }
+                if matched {
+                    return Some(j);
+                }
+                j += period;
+            }
+        }
+    }
+    None
+}
+
+/// An iterator over byte-string-separated pieces of a `ByteString`,
+/// created by `ByteString::split_str`.
+pub struct SplitStr<'a> {
+    haystack: Option<&'a [u8]>,
+    sep: &'a [u8],
+}
+
+impl<'a> Iterator<ByteString> for SplitStr<'a> {
+    fn next(&mut self) -> Option<ByteString> {
+        match self.haystack {
+            None => None,
+            Some(rest) => {
+                if self.sep.is_empty() {
+                    self.haystack = None;
+                    return Some(ByteString::from_bytes(rest));
+                }
+                match find_bytes(rest, self.sep) {
+                    Some(pos) => {
+                        self.haystack = Some(rest.slice_from(pos + self.sep.len()));
+                        Some(ByteString::from_bytes(rest.slice_to(pos)))
+                    }
+                    None => {
+                        self.haystack = None;
+                        Some(ByteString::from_bytes(rest))
+                    }
+                }
+            }
+        }
+    }
 }
 
 impl fmt::Show for ByteString {
-    /// Writes the underlying bytes as a `&[u8]`.
+    /// Writes this byte string as a double-quoted, lossily UTF-8 decoded
+    /// string. Bytes that aren't part of a valid, printable UTF-8
+    /// sequence (including stray invalid bytes and ASCII control
+    /// characters) are written as `\xHH` hex escapes instead, e.g.
+    /// `"caf\xe9"`. This is far more useful for eyeballing CSV data than
+    /// the raw `[255, 50, 48, ...]` byte-list most of this data would
+    /// otherwise print as.
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        // XXX: Ideally, we could just do this:
-        //
-        //    f.write(chars[])
-        //
-        // and let the output device figure out how to render it. But it seems
-        // the formatting infrastructure assumes that the data is UTF-8
-        // encodable, which obviously doesn't work with raw byte strings.
-        //
-        // For now, we just show the bytes, e.g., `[255, 50, 48, 49, ...]`.
-        write!(f, "{}", self[])
+        try!(write!(f, "\""));
+        let mut rest = self.as_bytes();
+        while !rest.is_empty() {
+            match decode_utf8_char(rest) {
+                (Some(c), len) if is_printable(c) => {
+                    try!(write!(f, "{}", c));
+                    rest = rest.slice_from(len);
+                }
+                (_, len) => {
+                    let len = if len == 0 { 1 } else { len };
+                    for b in rest.slice_to(len).iter() {
+                        try!(write!(f, "\\x{:02x}", *b));
+                    }
+                    rest = rest.slice_from(len);
+                }
+            }
+        }
+        write!(f, "\"")
     }
 }
 
+/// Returns `false` for ASCII and Latin-1 control characters, which are
+/// better shown as a hex escape than written through verbatim.
+fn is_printable(c: char) -> bool {
+    let cp = c as u32;
+    !(cp <= 0x1F || (cp >= 0x7F && cp <= 0x9F))
+}
+
+/// Attempts to decode a single Unicode scalar value from the front of
+/// `bytes`.
+///
+/// On success, returns the decoded `char` and the number of bytes it
+/// occupies. On failure, returns `None` along with the length of the
+/// maximal invalid subsequence, following the same "error run" rule used
+/// by `String::from_utf8_lossy`: scanning should resume at
+/// `bytes.slice_from(len)`, which is always the next byte that could
+/// begin a new sequence.
+///
+/// Overlong encodings, encoded surrogates, and codepoints past
+/// `U+10FFFF` are rejected by narrowing the valid range of the *first*
+/// continuation byte to what each lead byte can legally be followed by
+/// (e.g. `0xE0` must be followed by `0xA0..0xBF`, never `0x80..0x9F`,
+/// since the latter would be an overlong 3-byte sequence). That means a
+/// lead byte whose first continuation byte fails this narrowed check is,
+/// on its own, the entire invalid subsequence -- matching how
+/// `String::from_utf8_lossy` recovers from the same malformed input,
+/// rather than swallowing whatever well-formed-looking continuation
+/// bytes happen to follow it.
+fn decode_utf8_char(bytes: &[u8]) -> (Option<char>, uint) {
+    if bytes.is_empty() {
+        return (None, 0);
+    }
+    let b0 = bytes[0];
+    if b0 < 0x80 {
+        return (Some(b0 as char), 1);
+    }
+    let len =
+        if b0 & 0xE0 == 0xC0 {
+            2u
+        } else if b0 & 0xF0 == 0xE0 {
+            3u
+        } else if b0 & 0xF8 == 0xF0 {
+            4u
+        } else {
+            return (None, 1);
+        };
+    // `0xC0`/`0xC1` can only ever start an overlong 2-byte sequence, and
+    // `0xF5..0xFF` can only ever start a sequence past `U+10FFFF` -- both
+    // are invalid lead bytes on their own, regardless of what follows.
+    if b0 == 0xC0 || b0 == 0xC1 || b0 > 0xF4 {
+        return (None, 1);
+    }
+    if bytes.len() < 2 {
+        return (None, bytes.len());
+    }
+    let b1 = bytes[1];
+    let b1_in_range = match b0 {
+        0xE0 => b1 >= 0xA0 && b1 <= 0xBF,
+        0xED => b1 >= 0x80 && b1 <= 0x9F,
+        0xF0 => b1 >= 0x90 && b1 <= 0xBF,
+        0xF4 => b1 >= 0x80 && b1 <= 0x8F,
+        _ => b1 & 0xC0 == 0x80,
+    };
+    if !b1_in_range {
+        return (None, 1);
+    }
+    let mut cp = ((b0 & (0x7F >> len)) as u32) << 6 | (b1 & 0x3F) as u32;
+    // Validate every continuation byte we actually have before declaring
+    // the sequence merely "incomplete" -- a present-but-invalid byte at
+    // index 2 or 3 breaks the run right there, even if the buffer also
+    // happens to end before `len`, and those are two different error
+    // runs to `String::from_utf8_lossy`.
+    for i in range(2u, ::std::cmp::min(len, bytes.len())) {
+        let b = bytes[i];
+        if b & 0xC0 != 0x80 {
+            return (None, i);
+        }
+        cp = (cp << 6) | (b & 0x3F) as u32;
+    }
+    if bytes.len() < len {
+        return (None, bytes.len());
+    }
+    match ::std::char::from_u32(cp) {
+        Some(c) => (Some(c), len),
+        None => (None, len),
+    }
+}
+
+/// Decodes `bytes` as lossy UTF-8 using `decode_utf8_char`, replacing
+/// each maximal invalid subsequence with a single U+FFFD. This backs
+/// `into_utf8_string_lossy` and `to_utf8_lossy`; `chars`, `char_indices`
+/// and the `Show` impl call `decode_utf8_char` directly, so all of them
+/// split a given byte string into the same invalid runs (`Show` writes
+/// those runs as hex escapes instead of U+FFFD).
+fn utf8_lossy_string(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len());
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        match decode_utf8_char(rest) {
+            (Some(c), len) => {
+                s.push(c);
+                rest = rest.slice_from(len);
+            }
+            (None, len) => {
+                let len = if len == 0 { 1 } else { len };
+                s.push('\u{FFFD}');
+                rest = rest.slice_from(len);
+            }
+        }
+    }
+    s
+}
+
 impl AsSlice<u8> for ByteString {
     #[inline]
     fn as_slice<'a>(&'a self) -> &'a [u8] {
@@ -157,3 +775,854 @@ impl FromIterator<u8> for ByteString {
         ByteString::from_bytes(it.collect::<Vec<_>>())
     }
 }
+
+// ---------------------------------------------------------------------
+// Transcoding
+//
+// Many CSV files in the wild are not UTF-8 at all, but are instead
+// encoded in a legacy encoding such as Windows-1252 or ISO-8859-1. The
+// types below let callers decode (and encode) `ByteString` data with an
+// explicit `Encoding` instead of assuming UTF-8, while keeping the same
+// "bias toward *a* parse" philosophy as the rest of this module via the
+// `Trap` error-handling modes.
+//
+// TODO(follow-up, scope gap): this tree doesn't yet contain a CSV
+// reader/writer -- `src/` has only this one file -- so there is nothing
+// here to wire a default `Encoding` into. That means "reader/writer
+// configuration so the decode iterator can produce transcoded text
+// fields" is NOT implemented by what's in this file: `ByteString::decode_with`
+// is the full integration point for now, and it only covers transcoding
+// a single already-split field, not a reader's per-record decode loop.
+// Hooking a configured `Encoding` into that loop is unimplemented work
+// for whoever adds the reader/writer module, not something this file can
+// stand in for or be considered to have already closed out.
+// ---------------------------------------------------------------------
+
+/// Controls how an `Encoding` handles bytes (or characters) it cannot
+/// map, mirroring the WHATWG notion of an encoder/decoder "trap".
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub enum Trap {
+    /// Abort with an error on the first unmappable byte sequence.
+    Strict,
+    /// Substitute U+FFFD (on decode) or `?` (on encode) for each
+    /// unmappable sequence and continue.
+    Replace,
+    /// Silently drop each unmappable sequence and continue.
+    Ignore,
+}
+
+/// An error produced while decoding bytes that are not valid in the
+/// source `Encoding`, under `Trap::Strict`.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct DecodeError {
+    /// The byte offset of the first byte of the unmappable sequence.
+    pub position: uint,
+}
+
+/// An error produced while encoding a `char` that has no representation
+/// in the destination `Encoding`, under `Trap::Strict`.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub struct EncodeError {
+    /// The byte offset (into the source `&str`) of the unmappable
+    /// character.
+    pub position: uint,
+}
+
+/// A character encoding that can transcode between raw bytes and Unicode
+/// text, in the style of the WHATWG Encoding Standard.
+///
+/// Implementations are expected to be stateless and cheap to share by
+/// reference, since a single `Encoding` is typically used to decode every
+/// field of a CSV file.
+pub trait Encoding {
+    /// A human-readable name for this encoding, e.g. `"windows-1252"`.
+    fn name(&self) -> &'static str;
+
+    /// Decodes `bytes` into a `String`, handling unmappable or
+    /// ill-formed sequences according to `trap`.
+    fn decode(&self, bytes: &[u8], trap: Trap) -> Result<String, DecodeError>;
+
+    /// Encodes `s` into a byte vector, handling characters that have no
+    /// representation in this encoding according to `trap`.
+    fn encode(&self, s: &str, trap: Trap) -> Result<Vec<u8>, EncodeError>;
+}
+
+/// A single-byte encoding in which every byte `0x00..0x7F` is ASCII and
+/// every byte `0x80..0xFF` is looked up in a 128 entry table. This
+/// covers legacy encodings like Windows-1252 and ISO-8859-1, which make
+/// up the overwhelming majority of non-UTF-8 CSV files seen in practice.
+pub struct SingleByteEncoding {
+    name: &'static str,
+    high: [Option<char>, ..128],
+}
+
+impl SingleByteEncoding {
+    fn decode_high(&self, byte: u8) -> Option<char> {
+        self.high[(byte - 0x80) as uint]
+    }
+
+    fn encode_high(&self, c: char) -> Option<u8> {
+        for (i, slot) in self.high.iter().enumerate() {
+            if *slot == Some(c) {
+                return Some((0x80 + i) as u8);
+            }
+        }
+        None
+    }
+}
+
+impl Encoding for SingleByteEncoding {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn decode(&self, bytes: &[u8], trap: Trap) -> Result<String, DecodeError> {
+        let mut s = String::with_capacity(bytes.len());
+        for (i, &b) in bytes.iter().enumerate() {
+            if b < 0x80 {
+                s.push(b as char);
+                continue;
+            }
+            match self.decode_high(b) {
+                Some(c) => s.push(c),
+                None => match trap {
+                    Trap::Strict => return Err(DecodeError { position: i }),
+                    Trap::Replace => s.push('\u{FFFD}'),
+                    Trap::Ignore => {}
+                },
+            }
+        }
+        Ok(s)
+    }
+
+    fn encode(&self, s: &str, trap: Trap) -> Result<Vec<u8>, EncodeError> {
+        let mut out = Vec::with_capacity(s.len());
+        for (i, c) in s.char_indices() {
+            if (c as u32) < 0x80 {
+                out.push(c as u8);
+                continue;
+            }
+            match self.encode_high(c) {
+                Some(b) => out.push(b),
+                None => match trap {
+                    Trap::Strict => return Err(EncodeError { position: i }),
+                    Trap::Replace => out.push(b'?'),
+                    Trap::Ignore => {}
+                },
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// The ASCII encoding: every byte above `0x7F` is unmappable.
+pub fn ascii() -> SingleByteEncoding {
+    SingleByteEncoding { name: "ascii", high: [None, ..128] }
+}
+
+/// ISO-8859-1 (Latin-1): every byte maps directly to the Unicode scalar
+/// value of the same number.
+pub fn iso_8859_1() -> SingleByteEncoding {
+    let mut high = [None, ..128];
+    for i in range(0u, 128) {
+        high[i] = ::std::char::from_u32(0x80 + i as u32);
+    }
+    SingleByteEncoding { name: "iso-8859-1", high: high }
+}
+
+/// Windows-1252 (cp1252): identical to ISO-8859-1 except for the `0x80`
+/// through `0x9F` range, which Windows repurposes for punctuation and a
+/// handful of letters instead of the C1 control codes.
+pub fn windows_1252() -> SingleByteEncoding {
+    let mut high = [None, ..128];
+    for i in range(0u, 128) {
+        high[i] = ::std::char::from_u32(0x80 + i as u32);
+    }
+    static OVERRIDES: [(u8, char), ..27] = [
+        (0x80, '€'), (0x82, '‚'), (0x83, 'ƒ'), (0x84, '„'),
+        (0x85, '…'), (0x86, '†'), (0x87, '‡'), (0x88, 'ˆ'),
+        (0x89, '‰'), (0x8A, 'Š'), (0x8B, '‹'), (0x8C, 'Œ'),
+        (0x8E, 'Ž'), (0x91, '‘'), (0x92, '’'), (0x93, '“'),
+        (0x94, '”'), (0x95, '•'), (0x96, '–'), (0x97, '—'),
+        (0x98, '˜'), (0x99, '™'), (0x9A, 'š'), (0x9B, '›'),
+        (0x9C, 'œ'), (0x9E, 'ž'), (0x9F, 'Ÿ'),
+    ];
+    for &(byte, ch) in OVERRIDES.iter() {
+        high[(byte - 0x80) as uint] = Some(ch);
+    }
+    for &undefined in [0x81u8, 0x8Du8, 0x8Fu8, 0x90u8, 0x9Du8].iter() {
+        high[(undefined - 0x80) as uint] = None;
+    }
+    SingleByteEncoding { name: "windows-1252", high: high }
+}
+
+/// A Shift_JIS *subset*: only the parts that have a direct, table-free
+/// mapping to Unicode are decoded -- ASCII and the JIS X 0201 halfwidth
+/// katakana block (`0xA1..0xDF`, a fixed offset from `U+FF61..U+FF9F`).
+///
+/// Two-byte JIS X 0208 kanji sequences (lead bytes `0x81..0x9F` and
+/// `0xE0..0xFC`) are recognized well enough to consume the right number
+/// of bytes, but are not mapped to specific characters -- every such
+/// sequence is treated as unmappable. That full kanji table is real,
+/// non-algorithmic data entry that's a follow-up of its own, which is
+/// also why this type and `name()` are explicit about being a subset
+/// rather than calling themselves plain `"shift_jis"`: real-world
+/// Shift_JIS CSV is overwhelmingly kanji, so `Trap::Strict` will reject
+/// virtually every non-trivial file decoded with this, and `Trap::Replace`
+/// will replace virtually every character with it.
+pub struct ShiftJisAsciiKatakana;
+
+impl Encoding for ShiftJisAsciiKatakana {
+    fn name(&self) -> &'static str {
+        "shift_jis-ascii-katakana-subset"
+    }
+
+    fn decode(&self, bytes: &[u8], trap: Trap) -> Result<String, DecodeError> {
+        let mut s = String::with_capacity(bytes.len());
+        let mut i = 0u;
+        while i < bytes.len() {
+            let b = bytes[i];
+            if b < 0x80 {
+                s.push(b as char);
+                i += 1;
+            } else if b >= 0xA1 && b <= 0xDF {
+                s.push(::std::char::from_u32(0xFF61 + (b - 0xA1) as u32).unwrap());
+                i += 1;
+            } else if (b >= 0x81 && b <= 0x9F) || (b >= 0xE0 && b <= 0xFC) {
+                let len = if i + 1 < bytes.len() { 2 } else { 1 };
+                match trap {
+                    Trap::Strict => return Err(DecodeError { position: i }),
+                    Trap::Replace => s.push('\u{FFFD}'),
+                    Trap::Ignore => {}
+                }
+                i += len;
+            } else {
+                match trap {
+                    Trap::Strict => return Err(DecodeError { position: i }),
+                    Trap::Replace => s.push('\u{FFFD}'),
+                    Trap::Ignore => {}
+                }
+                i += 1;
+            }
+        }
+        Ok(s)
+    }
+
+    fn encode(&self, s: &str, trap: Trap) -> Result<Vec<u8>, EncodeError> {
+        let mut out = Vec::with_capacity(s.len());
+        for (i, c) in s.char_indices() {
+            let cp = c as u32;
+            if cp < 0x80 {
+                out.push(c as u8);
+            } else if cp >= 0xFF61 && cp <= 0xFF9F {
+                out.push((0xA1 + (cp - 0xFF61)) as u8);
+            } else {
+                match trap {
+                    Trap::Strict => return Err(EncodeError { position: i }),
+                    Trap::Replace => out.push(b'?'),
+                    Trap::Ignore => {}
+                }
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// The ASCII/halfwidth-katakana subset of Shift_JIS. See
+/// `ShiftJisAsciiKatakana` for what is (and, for now, isn't) mapped --
+/// full JIS X 0208 kanji support is tracked as a follow-up, not covered
+/// by this encoding.
+pub fn shift_jis_ascii_katakana() -> ShiftJisAsciiKatakana {
+    ShiftJisAsciiKatakana
+}
+
+/// A GBK *subset*: only ASCII is decoded. Two-byte lead bytes
+/// (`0x81..0xFE`) are recognized so `decode` consumes the right number
+/// of bytes instead of mis-splitting a double-byte character, but --
+/// like `ShiftJisAsciiKatakana` -- no double-byte conversion table is
+/// included, so every such sequence is treated as unmappable rather than
+/// guessed at. Real-world GBK CSV is overwhelmingly double-byte
+/// ideographs, so this type and `name()` are explicit about being an
+/// ASCII-only stub rather than calling themselves plain `"gbk"`; the full
+/// mapping table is a follow-up of its own.
+pub struct GbkAscii;
+
+impl Encoding for GbkAscii {
+    fn name(&self) -> &'static str {
+        "gbk-ascii-subset"
+    }
+
+    fn decode(&self, bytes: &[u8], trap: Trap) -> Result<String, DecodeError> {
+        let mut s = String::with_capacity(bytes.len());
+        let mut i = 0u;
+        while i < bytes.len() {
+            let b = bytes[i];
+            if b < 0x80 {
+                s.push(b as char);
+                i += 1;
+            } else if b >= 0x81 && b <= 0xFE {
+                let len = if i + 1 < bytes.len() { 2 } else { 1 };
+                match trap {
+                    Trap::Strict => return Err(DecodeError { position: i }),
+                    Trap::Replace => s.push('\u{FFFD}'),
+                    Trap::Ignore => {}
+                }
+                i += len;
+            } else {
+                match trap {
+                    Trap::Strict => return Err(DecodeError { position: i }),
+                    Trap::Replace => s.push('\u{FFFD}'),
+                    Trap::Ignore => {}
+                }
+                i += 1;
+            }
+        }
+        Ok(s)
+    }
+
+    fn encode(&self, s: &str, trap: Trap) -> Result<Vec<u8>, EncodeError> {
+        let mut out = Vec::with_capacity(s.len());
+        for (i, c) in s.char_indices() {
+            if (c as u32) < 0x80 {
+                out.push(c as u8);
+            } else {
+                match trap {
+                    Trap::Strict => return Err(EncodeError { position: i }),
+                    Trap::Replace => out.push(b'?'),
+                    Trap::Ignore => {}
+                }
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// The ASCII-only subset of GBK. See `GbkAscii` for what is (and, for
+/// now, isn't) mapped -- full double-byte GBK support is tracked as a
+/// follow-up, not covered by this encoding.
+pub fn gbk_ascii() -> GbkAscii {
+    GbkAscii
+}
+
+/// UTF-16, in either byte order. Unlike the legacy encodings above this
+/// maps every Unicode scalar value, including surrogate pairs for
+/// characters outside the Basic Multilingual Plane, so `detect_encoding`
+/// finding a UTF-16 byte-order mark is always actionable: this is the
+/// `Encoding` to transcode with once the BOM (see `SniffedEncoding`) has
+/// been stripped.
+pub struct Utf16 {
+    big_endian: bool,
+}
+
+impl Utf16 {
+    fn read_u16(&self, b0: u8, b1: u8) -> u16 {
+        if self.big_endian {
+            ((b0 as u16) << 8) | b1 as u16
+        } else {
+            ((b1 as u16) << 8) | b0 as u16
+        }
+    }
+
+    fn push_u16(&self, out: &mut Vec<u8>, unit: u16) {
+        if self.big_endian {
+            out.push((unit >> 8) as u8);
+            out.push((unit & 0xFF) as u8);
+        } else {
+            out.push((unit & 0xFF) as u8);
+            out.push((unit >> 8) as u8);
+        }
+    }
+}
+
+impl Encoding for Utf16 {
+    fn name(&self) -> &'static str {
+        if self.big_endian { "utf-16be" } else { "utf-16le" }
+    }
+
+    fn decode(&self, bytes: &[u8], trap: Trap) -> Result<String, DecodeError> {
+        let mut s = String::with_capacity(bytes.len() / 2);
+        let mut i = 0u;
+        while i < bytes.len() {
+            if i + 1 >= bytes.len() {
+                match trap {
+                    Trap::Strict => return Err(DecodeError { position: i }),
+                    Trap::Replace => s.push('\u{FFFD}'),
+                    Trap::Ignore => {}
+                }
+                i += 1;
+                continue;
+            }
+            let unit = self.read_u16(bytes[i], bytes[i + 1]);
+            if unit >= 0xD800 && unit <= 0xDBFF {
+                let mut decoded = false;
+                if i + 3 < bytes.len() {
+                    let unit2 = self.read_u16(bytes[i + 2], bytes[i + 3]);
+                    if unit2 >= 0xDC00 && unit2 <= 0xDFFF {
+                        let cp = 0x10000u32
+                            + (((unit - 0xD800) as u32) << 10)
+                            + (unit2 - 0xDC00) as u32;
+                        if let Some(c) = ::std::char::from_u32(cp) {
+                            s.push(c);
+                            i += 4;
+                            decoded = true;
+                        }
+                    }
+                }
+                if !decoded {
+                    match trap {
+                        Trap::Strict => return Err(DecodeError { position: i }),
+                        Trap::Replace => s.push('\u{FFFD}'),
+                        Trap::Ignore => {}
+                    }
+                    i += 2;
+                }
+            } else if unit >= 0xDC00 && unit <= 0xDFFF {
+                match trap {
+                    Trap::Strict => return Err(DecodeError { position: i }),
+                    Trap::Replace => s.push('\u{FFFD}'),
+                    Trap::Ignore => {}
+                }
+                i += 2;
+            } else {
+                match ::std::char::from_u32(unit as u32) {
+                    Some(c) => s.push(c),
+                    None => {}
+                }
+                i += 2;
+            }
+        }
+        Ok(s)
+    }
+
+    fn encode(&self, s: &str, _trap: Trap) -> Result<Vec<u8>, EncodeError> {
+        let mut out = Vec::with_capacity(s.len() * 2);
+        for c in s.chars() {
+            let cp = c as u32;
+            if cp <= 0xFFFF {
+                self.push_u16(&mut out, cp as u16);
+            } else {
+                let cp = cp - 0x10000;
+                self.push_u16(&mut out, 0xD800 + (cp >> 10) as u16);
+                self.push_u16(&mut out, 0xDC00 + (cp & 0x3FF) as u16);
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// UTF-16, little-endian. See `Utf16`.
+pub fn utf16_le() -> Utf16 {
+    Utf16 { big_endian: false }
+}
+
+/// UTF-16, big-endian. See `Utf16`.
+pub fn utf16_be() -> Utf16 {
+    Utf16 { big_endian: true }
+}
+
+// ---------------------------------------------------------------------
+// Encoding sniffing
+//
+// `detect_encoding` looks at the leading bytes of a `ByteString` for a
+// byte-order mark, and otherwise falls back to a UTF-8 well-formedness
+// heuristic. It's meant to be run once against the first bytes read from
+// a CSV source, with the result driving which `Encoding` a reader
+// transcodes the rest of the source with -- that reader-level wiring
+// belongs in the reader's configuration, which isn't part of this file.
+// ---------------------------------------------------------------------
+
+/// The result of sniffing a `ByteString`'s leading bytes for an encoding
+/// signature. See `ByteString::detect_encoding`.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub enum SniffedEncoding {
+    /// A UTF-8 byte-order mark (`EF BB BF`) was found; the bytes after
+    /// it are UTF-8.
+    Utf8Bom,
+    /// A UTF-16LE byte-order mark (`FF FE`) was found.
+    Utf16Le,
+    /// A UTF-16BE byte-order mark (`FE FF`) was found.
+    Utf16Be,
+    /// No byte-order mark was found, but the bytes are well-formed
+    /// UTF-8 (plain ASCII included).
+    Utf8,
+    /// No byte-order mark was found and the bytes are not well-formed
+    /// UTF-8. `detect_encoding` can't tell which legacy encoding is in
+    /// play, only that one is likely; callers should fall back to a
+    /// configured default `Encoding`, e.g. `windows_1252`.
+    Legacy,
+}
+
+impl SniffedEncoding {
+    /// The number of leading bytes that make up the byte-order mark, if
+    /// any. These should be skipped before transcoding the rest.
+    pub fn bom_len(&self) -> uint {
+        match *self {
+            SniffedEncoding::Utf8Bom => 3,
+            SniffedEncoding::Utf16Le | SniffedEncoding::Utf16Be => 2,
+            SniffedEncoding::Utf8 | SniffedEncoding::Legacy => 0,
+        }
+    }
+
+    /// The `Encoding` this sniff result implies the rest of the bytes
+    /// (after `bom_len` have been stripped) should be transcoded with,
+    /// if any.
+    ///
+    /// `Utf16Le`/`Utf16Be` found a BOM that pins down an exact encoding,
+    /// so this drives the transcoding layer automatically. `Utf8Bom` and
+    /// `Utf8` need no transcoding at all -- the bytes are already UTF-8 --
+    /// and `Legacy` only tells you *that* the data isn't UTF-8, not
+    /// *which* legacy encoding it is, so callers must fall back to a
+    /// configured default `Encoding` themselves.
+    pub fn encoding(&self) -> Option<Box<Encoding + 'static>> {
+        match *self {
+            SniffedEncoding::Utf16Le => Some(Box::new(utf16_le()) as Box<Encoding + 'static>),
+            SniffedEncoding::Utf16Be => Some(Box::new(utf16_be()) as Box<Encoding + 'static>),
+            SniffedEncoding::Utf8Bom | SniffedEncoding::Utf8 | SniffedEncoding::Legacy => None,
+        }
+    }
+}
+
+impl ByteString {
+    /// Inspects the leading bytes of this byte string for a byte-order
+    /// mark, and otherwise heuristically distinguishes UTF-8 from a
+    /// single-byte legacy encoding by checking whether the bytes are
+    /// well-formed UTF-8.
+    ///
+    /// Returns `None` only when there are no bytes to inspect at all.
+    pub fn detect_encoding(&self) -> Option<SniffedEncoding> {
+        let bytes = self.as_bytes();
+        if bytes.is_empty() {
+            return None;
+        }
+        if bytes.starts_with(&[0xEFu8, 0xBBu8, 0xBFu8]) {
+            return Some(SniffedEncoding::Utf8Bom);
+        }
+        if bytes.starts_with(&[0xFFu8, 0xFEu8]) {
+            return Some(SniffedEncoding::Utf16Le);
+        }
+        if bytes.starts_with(&[0xFEu8, 0xFFu8]) {
+            return Some(SniffedEncoding::Utf16Be);
+        }
+        if ::std::str::from_utf8(bytes).is_ok() {
+            Some(SniffedEncoding::Utf8)
+        } else {
+            Some(SniffedEncoding::Legacy)
+        }
+    }
+
+    /// Returns a copy of this byte string with the byte-order mark
+    /// described by `sniffed` (if any) stripped from the front. It always
+    /// allocates a new byte string, even when `sniffed` has no BOM, since
+    /// `ByteString` has no borrowed form -- but it keeps the BOM-stripping step explicit and
+    /// separate from the UTF-8-no-BOM fast path, which never needs to
+    /// call this at all.
+    pub fn strip_bom(&self, sniffed: SniffedEncoding) -> ByteString {
+        ByteString::from_bytes(self.as_bytes().slice_from(sniffed.bom_len()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ByteString, Encoding, SniffedEncoding, Trap, find_bytes};
+
+    fn naive_find(haystack: &[u8], needle: &[u8]) -> Option<uint> {
+        if needle.is_empty() {
+            return Some(0);
+        }
+        if needle.len() > haystack.len() {
+            return None;
+        }
+        for i in range(0u, haystack.len() - needle.len() + 1) {
+            if haystack.slice(i, i + needle.len()) == needle {
+                return Some(i);
+            }
+        }
+        None
+    }
+
+    /// A tiny xorshift32 PRNG so the fuzz test below is deterministic
+    /// without depending on `std::rand`.
+    struct Xorshift32 {
+        state: u32,
+    }
+
+    impl Xorshift32 {
+        fn new(seed: u32) -> Xorshift32 {
+            Xorshift32 { state: if seed == 0 { 1 } else { seed } }
+        }
+
+        fn next_u32(&mut self) -> u32 {
+            let mut x = self.state;
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            self.state = x;
+            x
+        }
+
+        fn next_byte(&mut self, alphabet: &[u8]) -> u8 {
+            alphabet[(self.next_u32() as uint) % alphabet.len()]
+        }
+    }
+
+    #[test]
+    fn find_known_cases() {
+        assert_eq!(find_bytes(b"foobar", b"bar"), Some(3));
+        assert_eq!(find_bytes(b"hello world", b"lo w"), Some(3));
+        assert_eq!(find_bytes(b"aab", b"ab"), Some(1));
+    }
+
+    #[test]
+    fn find_matches_naive_search() {
+        // A tiny two-byte alphabet maximizes the chance of repeated and
+        // overlapping substrings, which is exactly what stresses a
+        // Two-Way matcher's critical factorization and period handling.
+        let alphabet = b"ab";
+        let mut rng = Xorshift32::new(0xdeadbeef);
+        for _ in range(0u, 2000u) {
+            let hay_len = (rng.next_u32() as uint) % 12;
+            let needle_len = (rng.next_u32() as uint) % 6;
+            let haystack: Vec<u8> =
+                range(0u, hay_len).map(|_| rng.next_byte(alphabet)).collect();
+            let needle: Vec<u8> =
+                range(0u, needle_len).map(|_| rng.next_byte(alphabet)).collect();
+            let got = find_bytes(haystack.as_slice(), needle.as_slice());
+            let want = naive_find(haystack.as_slice(), needle.as_slice());
+            assert_eq!(got, want);
+        }
+    }
+
+    #[test]
+    fn rfind_basic() {
+        let bs = ByteString::from_bytes(b"abcabc".to_vec());
+        assert_eq!(bs.rfind(b"abc"), Some(3));
+        assert_eq!(bs.rfind(b"zzz"), None);
+    }
+
+    #[test]
+    fn contains_basic() {
+        let bs = ByteString::from_bytes(b"hello world".to_vec());
+        assert!(bs.contains(b"lo w"));
+        assert!(!bs.contains(b"xyz"));
+    }
+
+    #[test]
+    fn split_str_trailing_empty_field() {
+        let bs = ByteString::from_bytes(b"a,b,".to_vec());
+        let pieces: Vec<Vec<u8>> =
+            bs.split_str(b",").map(|p| p.into_bytes()).collect();
+        assert_eq!(pieces, vec![b"a".to_vec(), b"b".to_vec(), b"".to_vec()]);
+    }
+
+    #[test]
+    fn shift_jis_and_gbk_names_disclose_their_subset_scope() {
+        // Real Shift_JIS/GBK CSV is overwhelmingly multi-byte ideographs
+        // that neither of these subset encodings maps, so `name()` must
+        // not claim to be the full encoding.
+        assert_eq!(super::shift_jis_ascii_katakana().name(), "shift_jis-ascii-katakana-subset");
+        assert_eq!(super::gbk_ascii().name(), "gbk-ascii-subset");
+    }
+
+    #[test]
+    fn lossy_decoders_agree_on_replacement_count() {
+        // Includes an overlong two-byte encoding of NUL (`C0 80`), which
+        // is exactly the case where a hand-rolled lossy decoder and
+        // `String::from_utf8_lossy` can disagree about how many
+        // replacement characters to emit.
+        let bytes = vec![0x63u8, 0x61, 0x66, 0xC0, 0x80, 0x21];
+        let bs = ByteString::from_bytes(bytes);
+        let via_into = bs.clone().into_utf8_string_lossy();
+        let via_chars: String = bs.chars().collect();
+        assert_eq!(via_into, via_chars);
+        assert_eq!(bs.to_utf8_lossy().into_owned(), via_chars);
+    }
+
+    #[test]
+    fn lossy_decode_matches_std_error_run_length() {
+        // `0xC0 0x80` is an overlong encoding of NUL: `0xC0` can never be
+        // a valid lead byte, so it's its own one-byte error run, and the
+        // `0x80` that follows is a lone continuation byte with no lead --
+        // also its own one-byte error run. A decoder that instead treats
+        // the whole two bytes as a single invalid subsequence (because
+        // `0x80` happens to look like a structurally valid continuation
+        // byte) under-counts replacement characters relative to
+        // `String::from_utf8_lossy`.
+        let overlong = ByteString::from_bytes(vec![0x63u8, 0x61, 0x66, 0xC0, 0x80, 0x21]);
+        assert_eq!(overlong.clone().into_utf8_string_lossy(), "caf\u{FFFD}\u{FFFD}!");
+        assert_eq!(overlong.chars().collect::<String>(), "caf\u{FFFD}\u{FFFD}!");
+
+        // `0xED 0xA0 0x80` is a CESU-8 style encoded surrogate
+        // (U+D800): each byte is its own one-byte error run, since
+        // `0xED` followed by `0xA0` is never well-formed.
+        let surrogate = ByteString::from_bytes(vec![0xEDu8, 0xA0, 0x80]);
+        assert_eq!(surrogate.into_utf8_string_lossy(), "\u{FFFD}\u{FFFD}\u{FFFD}");
+
+        // `0xF4 0x90 0x80 0x80` would decode past U+10FFFF; again each
+        // byte is its own one-byte error run.
+        let out_of_range = ByteString::from_bytes(vec![0xF4u8, 0x90, 0x80, 0x80]);
+        assert_eq!(
+            out_of_range.into_utf8_string_lossy(),
+            "\u{FFFD}\u{FFFD}\u{FFFD}\u{FFFD}"
+        );
+
+        // `0xF0 0xA9 0xFA` is a 4-byte lead with a valid first
+        // continuation byte, but the buffer ends right after a second
+        // continuation byte (`0xFA`) that is itself not a valid
+        // continuation byte. `0xF0 0xA9` is one error run (the lead plus
+        // its one good continuation byte, cut short); `0xFA` -- neither a
+        // continuation byte nor a valid lead byte -- is a second,
+        // separate error run. Lumping all three bytes into a single
+        // "incomplete sequence" error under-counts relative to
+        // `String::from_utf8_lossy`.
+        let truncated_with_bad_tail = ByteString::from_bytes(vec![0xF0u8, 0xA9, 0xFA]);
+        assert_eq!(truncated_with_bad_tail.into_utf8_string_lossy(), "\u{FFFD}\u{FFFD}");
+    }
+
+    #[test]
+    fn detect_encoding_finds_boms_and_strips_them() {
+        let utf8_bom = ByteString::from_bytes(vec![0xEFu8, 0xBBu8, 0xBFu8, b'h', b'i']);
+        let sniffed = utf8_bom.detect_encoding().unwrap();
+        assert_eq!(sniffed, SniffedEncoding::Utf8Bom);
+        assert_eq!(utf8_bom.strip_bom(sniffed).into_bytes(), vec![b'h', b'i']);
+
+        let utf16_be = ByteString::from_bytes(vec![0xFEu8, 0xFFu8, 0x00, b'h', 0x00, b'i']);
+        let sniffed = utf16_be.detect_encoding().unwrap();
+        assert_eq!(sniffed, SniffedEncoding::Utf16Be);
+        assert_eq!(utf16_be.strip_bom(sniffed).into_bytes(), vec![0x00u8, b'h', 0x00, b'i']);
+
+        let plain_utf8 = ByteString::from_bytes(vec![b'h', b'i']);
+        assert_eq!(plain_utf8.detect_encoding(), Some(SniffedEncoding::Utf8));
+        assert_eq!(
+            plain_utf8.strip_bom(SniffedEncoding::Utf8).into_bytes(),
+            vec![b'h', b'i']
+        );
+
+        let legacy = ByteString::from_bytes(vec![b'h', 0xFFu8, b'i']);
+        assert_eq!(legacy.detect_encoding(), Some(SniffedEncoding::Legacy));
+
+        let empty = ByteString::from_bytes(Vec::new());
+        assert_eq!(empty.detect_encoding(), None);
+    }
+
+    #[test]
+    fn decode_with_accepts_the_boxed_encoding_sniffing_returns() {
+        // `SniffedEncoding::encoding` hands back a `Box<Encoding>` trait
+        // object, not a `Sized` type -- `decode_with` has to accept that
+        // or the sniff-then-decode workflow (sniff, then decode with
+        // whatever was sniffed) doesn't compile.
+        let bs = ByteString::from_bytes(vec![0xFFu8, 0xFEu8, 0x41, 0x00, 0x42, 0x00]);
+        let sniffed = bs.detect_encoding().unwrap();
+        assert_eq!(sniffed, SniffedEncoding::Utf16Le);
+        let stripped = bs.strip_bom(sniffed);
+        let enc = sniffed.encoding().unwrap();
+        assert_eq!(stripped.decode_with(&*enc, Trap::Strict).unwrap(), "AB");
+    }
+
+    #[test]
+    fn single_byte_encodings_round_trip_and_trap() {
+        let ascii = super::ascii();
+        assert_eq!(ascii.decode(b"abc", Trap::Strict).unwrap(), "abc".to_string());
+        assert!(ascii.decode(&[0x80u8], Trap::Strict).is_err());
+        assert_eq!(ascii.decode(&[0x80u8], Trap::Replace).unwrap(), "\u{FFFD}".to_string());
+        assert_eq!(ascii.decode(&[0x80u8], Trap::Ignore).unwrap(), "".to_string());
+
+        let latin1 = super::iso_8859_1();
+        assert_eq!(latin1.decode(&[0xE9u8], Trap::Strict).unwrap(), "\u{E9}".to_string());
+        assert_eq!(latin1.encode("\u{E9}", Trap::Strict).unwrap(), vec![0xE9u8]);
+
+        let cp1252 = super::windows_1252();
+        assert_eq!(cp1252.decode(&[0x80u8], Trap::Strict).unwrap(), "\u{20AC}".to_string());
+        assert_eq!(cp1252.encode("\u{20AC}", Trap::Strict).unwrap(), vec![0x80u8]);
+        // 0x81 is one of Windows-1252's undefined C1 slots.
+        assert!(cp1252.decode(&[0x81u8], Trap::Strict).is_err());
+        assert_eq!(cp1252.encode("\u{1F600}", Trap::Replace).unwrap(), b"?".to_vec());
+    }
+
+    #[test]
+    fn shift_jis_and_gbk_decode_ascii_and_trap_on_double_byte() {
+        let sjis = super::shift_jis_ascii_katakana();
+        assert_eq!(sjis.decode(b"ab", Trap::Strict).unwrap(), "ab".to_string());
+        // 0xB1 is JIS X 0201 halfwidth katakana, directly mapped.
+        assert_eq!(sjis.decode(&[0xB1u8], Trap::Strict).unwrap(), "\u{FF71}".to_string());
+        // 0x82 0xA0 is a two-byte kanji lead/trail pair; unmapped, and
+        // both bytes should be consumed by a single Strict error.
+        assert_eq!(sjis.decode(&[0x82u8, 0xA0], Trap::Strict).unwrap_err().position, 0);
+        assert_eq!(sjis.decode(&[0x82u8, 0xA0], Trap::Replace).unwrap(), "\u{FFFD}".to_string());
+
+        let gbk = super::gbk_ascii();
+        assert_eq!(gbk.decode(b"ab", Trap::Strict).unwrap(), "ab".to_string());
+        assert_eq!(gbk.decode(&[0x81u8, 0x40], Trap::Replace).unwrap(), "\u{FFFD}".to_string());
+        assert!(gbk.decode(&[0x81u8, 0x40], Trap::Strict).is_err());
+    }
+
+    #[test]
+    fn utf16_round_trips_bmp_and_surrogate_pairs() {
+        let le = super::utf16_le();
+        let be = super::utf16_be();
+
+        let bytes = le.encode("A\u{1F600}", Trap::Strict).unwrap();
+        assert_eq!(le.decode(bytes.as_slice(), Trap::Strict).unwrap(), "A\u{1F600}".to_string());
+
+        let bytes = be.encode("A\u{1F600}", Trap::Strict).unwrap();
+        assert_eq!(be.decode(bytes.as_slice(), Trap::Strict).unwrap(), "A\u{1F600}".to_string());
+
+        // A lone high surrogate unit (no matching low surrogate follows),
+        // encoded big-endian: 0xD800 is `D8 00`.
+        let lone_high = [0xD8u8, 0x00u8];
+        assert!(be.decode(&lone_high, Trap::Strict).is_err());
+        assert_eq!(be.decode(&lone_high, Trap::Replace).unwrap(), "\u{FFFD}".to_string());
+    }
+
+    #[test]
+    fn chars_and_char_indices_agree_on_invalid_runs() {
+        let bs = ByteString::from_bytes(vec![0x63u8, 0x61, 0x66, 0xC0, 0x80, 0x21]);
+        let chars: Vec<char> = bs.chars().collect();
+        assert_eq!(chars, vec!['c', 'a', 'f', '\u{FFFD}', '\u{FFFD}', '!']);
+
+        let indices: Vec<(uint, uint, char)> = bs.char_indices().collect();
+        assert_eq!(
+            indices,
+            vec![
+                (0, 1, 'c'), (1, 2, 'a'), (2, 3, 'f'),
+                (3, 4, '\u{FFFD}'), (4, 5, '\u{FFFD}'), (5, 6, '!'),
+            ]
+        );
+    }
+
+    #[test]
+    fn chars_splits_truncated_four_byte_lead_from_bad_tail_byte() {
+        // `0xF0 0xA9` is one error run (a 4-byte lead cut short after one
+        // good continuation byte); `0xFA` -- not a valid continuation
+        // byte, and with no lead of its own -- is a second, separate
+        // error run. This should decode to two replacement characters,
+        // not one, matching `String::from_utf8_lossy`.
+        let bs = ByteString::from_bytes(vec![0xF0u8, 0xA9, 0xFA]);
+        assert_eq!(bs.chars().collect::<String>(), "\u{FFFD}\u{FFFD}");
+        assert_eq!(
+            bs.char_indices().collect::<Vec<(uint, uint, char)>>(),
+            vec![(0, 2, '\u{FFFD}'), (2, 3, '\u{FFFD}')]
+        );
+    }
+
+    #[test]
+    fn graphemes_group_combining_marks_and_crlf() {
+        // "e" + COMBINING ACUTE ACCENT (U+0301) is one grapheme cluster.
+        let mut bytes = b"e".to_vec();
+        bytes.push_all(&[0xCCu8, 0x81]); // UTF-8 for U+0301
+        bytes.push_all(b"\r\nx");
+        let bs = ByteString::from_bytes(bytes);
+        let clusters: Vec<String> = bs.graphemes().collect();
+        assert_eq!(clusters, vec!["e\u{0301}".to_string(), "\r\n".to_string(), "x".to_string()]);
+    }
+
+    #[test]
+    fn split_str_multi_byte_separator() {
+        let bs = ByteString::from_bytes(b"one::two::three".to_vec());
+        let pieces: Vec<Vec<u8>> =
+            bs.split_str(b"::").map(|p| p.into_bytes()).collect();
+        assert_eq!(
+            pieces,
+            vec![b"one".to_vec(), b"two".to_vec(), b"three".to_vec()]
+        );
+    }
+}